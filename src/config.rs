@@ -1,8 +1,9 @@
 /* SPDX-License-Identifier: MIT */
 
+use crate::error::{Error, ResultExt};
 use crate::generator::run_generator;
-use crate::ResultExt;
-use failure::Error;
+use crate::manage::run_manager;
+use crate::setup::{run_device_setup, run_writeback};
 use ini::Ini;
 use std::borrow::Cow;
 use std::env;
@@ -16,6 +17,16 @@ pub struct Device {
     pub name: String,
     pub memory_limit_mb: u64,
     pub zram_fraction: f64,
+    pub compression_algorithm: Option<String>,
+    pub mem_limit_mb: Option<u64>,
+    pub writeback_device: Option<PathBuf>,
+    pub writeback_idle_seconds: Option<u64>,
+    pub writeback_limit: Option<u64>,
+    pub pressure_high: Option<f64>,
+    pub pressure_low: f64,
+    pub min_devices: u64,
+    pub max_devices: u64,
+    pub poll_interval_sec: u64,
     pub disksize: u64,
 }
 
@@ -25,6 +36,16 @@ impl Device {
             name,
             memory_limit_mb: 2 * 1024,
             zram_fraction: 0.25,
+            compression_algorithm: None,
+            mem_limit_mb: None,
+            writeback_device: None,
+            writeback_idle_seconds: None,
+            writeback_limit: None,
+            pressure_high: None,
+            pressure_low: 0.,
+            min_devices: 0,
+            max_devices: 1,
+            poll_interval_sec: 10,
             disksize: 0,
         }
     }
@@ -40,6 +61,8 @@ pub struct Config {
 pub enum ModuleConfig {
     Generator { output_directory: PathBuf },
     DeviceSetup { name: String },
+    Writeback { name: String },
+    Manage,
 }
 
 
@@ -61,20 +84,28 @@ impl Config {
                     "--setup-device" =>
                         ModuleConfig::DeviceSetup {
                             name: args.next()
-                                      .filter(|dev| &dev[0..4] == "zram")
-                                      .ok_or_else(|| failure::err_msg("--setup-device requires device argument"))?
+                                      .filter(|dev| dev.starts_with("zram"))
+                                      .ok_or(Error::SetupDeviceArgument)?
                         },
+                    "--writeback" =>
+                        ModuleConfig::Writeback {
+                            name: args.next()
+                                      .filter(|dev| dev.starts_with("zram"))
+                                      .ok_or(Error::WritebackArgument)?
+                        },
+                    "--manage" =>
+                        ModuleConfig::Manage,
                     _ =>
                         match (args.next(), args.next(), args.next()) {
                             (Some(_), Some(_), None) |
                             (None, None, None) =>
                                 ModuleConfig::Generator { output_directory: PathBuf::from(outdir) },
                             _ =>
-                                return Err(failure::err_msg("This program requires 1 or 3 arguments")),
+                                return Err(Error::WrongArgumentCount),
                         }
                 }
             }
-            None => return Err(failure::err_msg("This program requires 1 or 3 arguments")),
+            None => return Err(Error::WrongArgumentCount),
         };
 
         let devices = Config::read_devices(&root)?;
@@ -90,7 +121,9 @@ impl Config {
 
         let memtotal_mb = get_total_memory_kb(&root)? as f64 / 1024.;
 
-        Result::from_iter(Ini::load_from_file(&path).with_path(&path)?.into_iter().map(|(section_name, section)| {
+        let ini = Ini::load_from_file(&path).map_err(|source| Error::ConfigLoad { path: path.clone(), source })?;
+
+        Result::from_iter(ini.into_iter().map(|(section_name, section)| {
             let section_name = section_name.map(Cow::Owned).unwrap_or(Cow::Borrowed("(no title)"));
 
             if !section_name.starts_with("zram") {
@@ -98,59 +131,188 @@ impl Config {
                 return Ok(None);
             }
 
-            let mut dev = Device::new(section_name.into_owned());
-
-            if let Some(val) = section.get("memory-limit") {
-                if val == "none" {
-                    dev.memory_limit_mb = u64::max_value();
-                } else {
-                    dev.memory_limit_mb = val.parse()
-                        .map_err(|e| format_err!("Failed to parse memory-limit \"{}\": {}", val, e))?;
-                }
-            }
-
-            if let Some(val) = section.get("zram-fraction") {
-                dev.zram_fraction = val.parse()
-                    .map_err(|e| format_err!("Failed to parse zram-fraction \"{}\": {}", val, e))?;
-            }
-
-            println!("Found configuration for {}: memory-limit={}MB zram-fraction={}",
-                     dev.name, dev.memory_limit_mb, dev.zram_fraction);
-
-            if memtotal_mb > dev.memory_limit_mb as f64 {
-                println!("{}: system has too much memory ({:.1}MB), limit is {}MB, ignoring.",
-                         dev.name,
-                         memtotal_mb,
-                         dev.memory_limit_mb);
-                Ok(None)
-            } else {
-                dev.disksize = (dev.zram_fraction * memtotal_mb) as u64 * 1024 * 1024;
-                Ok(Some(dev))
-            }
+            parse_device(&section_name, &section, memtotal_mb)
         }).map(Result::transpose).flatten())
     }
 
     pub fn run(self) -> Result<(), Error> {
         match self.module {
             ModuleConfig::Generator { output_directory } => run_generator(self.root, self.devices, output_directory),
-            ModuleConfig::DeviceSetup { name } => unimplemented!("setting up for {}", name),
+            ModuleConfig::DeviceSetup { name } => {
+                let device = self.devices.iter()
+                    .find(|dev| dev.name == name)
+                    .ok_or_else(|| Error::UnknownDevice(name.clone()))?;
+                run_device_setup(self.root, device)
+            }
+            ModuleConfig::Writeback { name } => {
+                let device = self.devices.iter()
+                    .find(|dev| dev.name == name)
+                    .ok_or_else(|| Error::UnknownDevice(name.clone()))?;
+                run_writeback(self.root, device)
+            }
+            ModuleConfig::Manage => run_manager(self.root, self.devices),
         }
     }
 }
 
 
+fn parse_device(name: &str, section: &ini::Properties, memtotal_mb: f64) -> Result<Option<Device>, Error> {
+    let mut dev = Device::new(name.to_owned());
+
+    if let Some(val) = section.get("memory-limit") {
+        if val == "none" {
+            dev.memory_limit_mb = u64::max_value();
+        } else {
+            dev.memory_limit_mb = val.parse()
+                .map_err(|source| Error::MemoryLimit { value: val.to_string(), source })?;
+        }
+    }
+
+    if let Some(val) = section.get("zram-fraction") {
+        dev.zram_fraction = val.parse()
+            .map_err(|source| Error::ZramFraction { value: val.to_string(), source })?;
+    }
+
+    if let Some(val) = section.get("compression-algorithm") {
+        dev.compression_algorithm = Some(val.to_string());
+    }
+
+    if let Some(val) = section.get("zram-mem-limit") {
+        dev.mem_limit_mb = Some(val.parse()
+            .map_err(|source| Error::MemLimit { value: val.to_string(), source })?);
+    }
+
+    if let Some(val) = section.get("writeback-device") {
+        dev.writeback_device = Some(PathBuf::from(val));
+    }
+
+    if let Some(val) = section.get("writeback-idle-seconds") {
+        dev.writeback_idle_seconds = Some(val.parse()
+            .map_err(|source| Error::WritebackIdleSeconds { value: val.to_string(), source })?);
+    }
+
+    if let Some(val) = section.get("writeback-limit") {
+        dev.writeback_limit = Some(val.parse()
+            .map_err(|source| Error::WritebackLimit { value: val.to_string(), source })?);
+    }
+
+    if let Some(val) = section.get("pressure-high-watermark") {
+        dev.pressure_high = Some(val.parse()
+            .map_err(|source| Error::PressureWatermark { value: val.to_string(), source })?);
+    }
+
+    if let Some(val) = section.get("pressure-low-watermark") {
+        dev.pressure_low = val.parse()
+            .map_err(|source| Error::PressureWatermark { value: val.to_string(), source })?;
+    }
+
+    if let Some(val) = section.get("min-devices") {
+        dev.min_devices = val.parse()
+            .map_err(|source| Error::DeviceCount { value: val.to_string(), source })?;
+    }
+
+    if let Some(val) = section.get("max-devices") {
+        dev.max_devices = val.parse()
+            .map_err(|source| Error::DeviceCount { value: val.to_string(), source })?;
+    }
+
+    if let Some(val) = section.get("poll-interval-seconds") {
+        dev.poll_interval_sec = val.parse()
+            .map_err(|source| Error::PollInterval { value: val.to_string(), source })?;
+    }
+
+    println!("Found configuration for {}: memory-limit={}MB zram-fraction={}",
+             dev.name, dev.memory_limit_mb, dev.zram_fraction);
+
+    if memtotal_mb > dev.memory_limit_mb as f64 {
+        println!("{}: system has too much memory ({:.1}MB), limit is {}MB, ignoring.",
+                 dev.name,
+                 memtotal_mb,
+                 dev.memory_limit_mb);
+        Ok(None)
+    } else {
+        dev.disksize = (dev.zram_fraction * memtotal_mb) as u64 * 1024 * 1024;
+        Ok(Some(dev))
+    }
+}
+
+
 fn get_total_memory_kb(root: &str) -> Result<u64, Error> {
     let path = Path::new(root).join("proc/meminfo");
 
     for line in BufReader::new(fs::File::open(&path).with_path(&path)?).lines() {
-        let line = line?;
+        let line = line.with_path(&path)?;
         let mut fields = line.split_whitespace();
         if let Some("MemTotal:") = fields.next() {
             if let Some(v) = fields.next() {
-                return Ok(v.parse()?);
+                return v.parse().map_err(|source| Error::MemTotal { value: v.to_string(), source });
             }
         }
     }
 
-    Err(format_err!("Couldn't find MemTotal in {}", path.display()))
+    Err(Error::NoMemTotal(path))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_device;
+    use crate::error::Error;
+    use ini::Ini;
+
+    fn section(body: &str) -> Ini {
+        Ini::load_from_str(body).unwrap()
+    }
+
+    #[test]
+    fn parses_new_keys() {
+        let ini = section("[zram0]\n\
+                           zram-fraction=0.5\n\
+                           compression-algorithm=zstd\n\
+                           zram-mem-limit=512\n\
+                           writeback-device=/dev/sda2\n\
+                           writeback-idle-seconds=3600\n\
+                           pressure-high-watermark=20\n\
+                           pressure-low-watermark=5\n\
+                           min-devices=0\n\
+                           max-devices=4\n\
+                           poll-interval-seconds=30\n");
+        let dev = parse_device("zram0", ini.section(Some("zram0")).unwrap(), 1024.)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(dev.compression_algorithm.as_deref(), Some("zstd"));
+        assert_eq!(dev.mem_limit_mb, Some(512));
+        assert_eq!(dev.writeback_device.unwrap().to_str(), Some("/dev/sda2"));
+        assert_eq!(dev.writeback_idle_seconds, Some(3600));
+        assert_eq!(dev.pressure_high, Some(20.));
+        assert_eq!(dev.pressure_low, 5.);
+        assert_eq!(dev.min_devices, 0);
+        assert_eq!(dev.max_devices, 4);
+        assert_eq!(dev.poll_interval_sec, 30);
+        assert_eq!(dev.disksize, (0.5 * 1024.) as u64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn min_devices_defaults_to_zero() {
+        let ini = section("[zram0]\n");
+        let dev = parse_device("zram0", ini.section(Some("zram0")).unwrap(), 1024.)
+            .unwrap()
+            .unwrap();
+        assert_eq!(dev.min_devices, 0);
+    }
+
+    #[test]
+    fn too_much_memory_skips_device() {
+        let ini = section("[zram0]\nmemory-limit=100\n");
+        let dev = parse_device("zram0", ini.section(Some("zram0")).unwrap(), 2048.).unwrap();
+        assert!(dev.is_none());
+    }
+
+    #[test]
+    fn bad_fraction_is_an_error() {
+        let ini = section("[zram0]\nzram-fraction=notanumber\n");
+        let err = parse_device("zram0", ini.section(Some("zram0")).unwrap(), 1024.).unwrap_err();
+        assert!(matches!(err, Error::ZramFraction { .. }));
+    }
 }
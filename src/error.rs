@@ -0,0 +1,85 @@
+/* SPDX-License-Identifier: MIT */
+
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use thiserror::Error;
+
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("This program requires 1 or 3 arguments")]
+    WrongArgumentCount,
+    #[error("--setup-device requires a zramN device argument")]
+    SetupDeviceArgument,
+    #[error("--writeback requires a zramN device argument")]
+    WritebackArgument,
+    #[error("No configuration found for {0}")]
+    UnknownDevice(String),
+
+    #[error("Failed to parse memory-limit \"{value}\": {source}")]
+    MemoryLimit { value: String, #[source] source: ParseIntError },
+    #[error("Failed to parse zram-fraction \"{value}\": {source}")]
+    ZramFraction { value: String, #[source] source: ParseFloatError },
+    #[error("Failed to parse writeback-idle-seconds \"{value}\": {source}")]
+    WritebackIdleSeconds { value: String, #[source] source: ParseIntError },
+    #[error("Failed to parse zram-mem-limit \"{value}\": {source}")]
+    MemLimit { value: String, #[source] source: ParseIntError },
+    #[error("Failed to parse writeback-limit \"{value}\": {source}")]
+    WritebackLimit { value: String, #[source] source: ParseIntError },
+    #[error("Failed to parse pressure watermark \"{value}\": {source}")]
+    PressureWatermark { value: String, #[source] source: ParseFloatError },
+    #[error("Failed to parse device count \"{value}\": {source}")]
+    DeviceCount { value: String, #[source] source: ParseIntError },
+    #[error("Failed to parse poll-interval-seconds \"{value}\": {source}")]
+    PollInterval { value: String, #[source] source: ParseIntError },
+    #[error("Failed to load configuration from {}: {source}", .path.display())]
+    ConfigLoad { path: PathBuf, #[source] source: ini::Error },
+
+    #[error("Couldn't find MemTotal in {}", .0.display())]
+    NoMemTotal(PathBuf),
+    #[error("Failed to parse MemTotal \"{value}\": {source}")]
+    MemTotal { value: String, #[source] source: ParseIntError },
+
+    #[error("Couldn't get parent of {}", .0.display())]
+    Orphan(PathBuf),
+    #[error("systemd-detect-virt call failed: {0}")]
+    DetectVirt(#[source] io::Error),
+    #[error("Couldn't determine our own path: {0}")]
+    SelfPath(#[source] io::Error),
+
+    #[error("zram-control allocated {allocated}, but {requested} was configured; preallocate it via zram.num_devices")]
+    AllocatedIndexMismatch { requested: String, allocated: String },
+    #[error("mkswap call failed: {0}")]
+    Mkswap(#[source] io::Error),
+    #[error("mkswap {} failed: {status}", .device.display())]
+    MkswapStatus { device: PathBuf, status: ExitStatus },
+    #[error("Couldn't parse memory pressure from {}", .0.display())]
+    PressureParse(PathBuf),
+    #[error("Only one [zramN] section may configure pressure management")]
+    MultipleManagedSections,
+    #[error("swapon call failed: {0}")]
+    Swapon(#[source] io::Error),
+    #[error("swapon {} failed: {status}", .device.display())]
+    SwaponStatus { device: PathBuf, status: ExitStatus },
+    #[error("swapoff call failed: {0}")]
+    Swapoff(#[source] io::Error),
+    #[error("swapoff {} failed: {status}", .device.display())]
+    SwapoffStatus { device: PathBuf, status: ExitStatus },
+
+    #[error("{}: {source}", .path.display())]
+    Io { path: PathBuf, #[source] source: io::Error },
+}
+
+
+/// Attach the offending path to an [`io::Error`], turning it into [`Error::Io`].
+pub trait ResultExt<T> {
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T, Error>;
+}
+
+impl<T> ResultExt<T> for Result<T, io::Error> {
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T, Error> {
+        self.map_err(|source| Error::Io { path: path.into(), source })
+    }
+}
@@ -1,9 +1,9 @@
 /* SPDX-License-Identifier: MIT */
 
 use crate::config::Device;
-use crate::ResultExt;
-use failure::Error;
+use crate::error::{Error, ResultExt};
 use std::borrow::Cow;
+use std::env;
 use std::fs;
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
@@ -12,8 +12,8 @@ use std::process::{Command, Stdio};
 
 fn make_parent(of: &Path) -> Result<(), Error> {
     let parent = of.parent()
-        .ok_or_else(|| format_err!("Couldn't get parent of {}", of.display()))?;
-    fs::create_dir_all(&parent)?;
+        .ok_or_else(|| Error::Orphan(of.to_path_buf()))?;
+    fs::create_dir_all(&parent).with_path(parent)?;
     Ok(())
 }
 
@@ -26,7 +26,7 @@ fn make_symlink(dst: &str, src: &Path) -> Result<(), Error> {
 fn virtualization_container() -> Result<bool, Error> {
     match Command::new("systemd-detect-virt").arg("--container").stdout(Stdio::null()).status() {
         Ok(status) => Ok(status.success()),
-        Err(e) => Err(format_err!("systemd-detect-virt call failed: {}", e)),
+        Err(e) => Err(Error::DetectVirt(e)),
     }
 }
 
@@ -37,9 +37,11 @@ pub fn run_generator(root: Cow<'static, str>, devices: Vec<Device>, output_direc
         return Ok(());
     }
 
+    let zram_generator = env::current_exe().map_err(Error::SelfPath)?;
+
     let mut devices_made = false;
     for dev in &devices {
-        devices_made |= handle_device(&root, &output_directory, dev)?;
+        devices_made |= handle_device(&root, &output_directory, &zram_generator, dev)?;
     }
     if devices_made {
         /* We created some services, let's make sure the module is loaded */
@@ -51,7 +53,7 @@ pub fn run_generator(root: Cow<'static, str>, devices: Vec<Device>, output_direc
     Ok(())
 }
 
-fn handle_device(root: &str, output_directory: &Path, device: &Device) -> Result<bool, Error> {
+fn handle_device(root: &str, output_directory: &Path, zram_generator: &Path, device: &Device) -> Result<bool, Error> {
     let service_name = format!("swap-create@{}.service", device.name);
     println!("Creating {} for {}dev/{} ({}MB)",
              service_name, root, device.name, device.disksize / 1024 / 1024);
@@ -69,12 +71,11 @@ DefaultDependencies=false
 [Service]
 Type=oneshot
 ExecStartPre=-modprobe zram
-ExecStart=sh -c 'echo {disksize} >{root}sys/block/%i/disksize'
-ExecStart=mkswap {root}dev/%i
+ExecStart={zram_generator} --setup-device %i
 ",
         root = root,
         device_name = format!("dev-{}.device", device.name),
-        disksize = device.disksize,
+        zram_generator = zram_generator.display(),
     );
     fs::write(&service_path, contents).with_path(service_path)?;
 
@@ -101,5 +102,54 @@ Options=pri=100
     let symlink_path = output_directory.join("swap.target.wants").join(&swap_name);
     let target_path = format!("../{}", swap_name);
     make_symlink(&target_path, &symlink_path)?;
+
+    if device.writeback_device.is_some() {
+        handle_writeback(output_directory, zram_generator, device)?;
+    }
+
     Ok(true)
 }
+
+fn handle_writeback(output_directory: &Path, zram_generator: &Path, device: &Device) -> Result<(), Error> {
+    let service_name = format!("swap-writeback@{}.service", device.name);
+    let service_path = output_directory.join(&service_name);
+
+    let contents = format!("\
+[Unit]
+Description=Write back idle pages of %i to its backing device
+Requires=swap-create@%i.service
+After=swap-create@%i.service
+
+[Service]
+Type=oneshot
+ExecStart={zram_generator} --writeback %i
+",
+        zram_generator = zram_generator.display(),
+    );
+    fs::write(&service_path, contents).with_path(service_path)?;
+
+    let timer_name = format!("swap-writeback@{}.timer", device.name);
+    let timer_path = output_directory.join(&timer_name);
+
+    let contents = format!("\
+[Unit]
+Description=Periodically write back idle pages of {zram_device}
+
+[Timer]
+OnBootSec=1h
+OnUnitActiveSec=1h
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+",
+        zram_device = device.name,
+    );
+    fs::write(&timer_path, contents).with_path(timer_path)?;
+
+    let symlink_path = output_directory.join("timers.target.wants").join(&timer_name);
+    let target_path = format!("../{}", timer_name);
+    make_symlink(&target_path, &symlink_path)?;
+
+    Ok(())
+}
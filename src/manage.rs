@@ -0,0 +1,148 @@
+/* SPDX-License-Identifier: MIT */
+
+use crate::config::Device;
+use crate::error::{Error, ResultExt};
+use crate::setup;
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+
+/// The `full` avg10 memory stall, in percent, as reported by PSI.
+struct Pressure {
+    full_avg10: f64,
+}
+
+
+pub fn run_manager(root: Cow<'static, str>, devices: Vec<Device>) -> Result<(), Error> {
+    let base = PathBuf::from(&root[..]);
+
+    let mut managed_sections = devices.iter().filter(|dev| dev.pressure_high.is_some());
+    let template = match managed_sections.next() {
+        Some(dev) => dev,
+        None => {
+            println!("No device configured for pressure management, exiting.");
+            return Ok(());
+        }
+    };
+    /* Scaling is driven from a single template section; more than one would be
+     * ambiguous, so refuse rather than silently honour only the first. */
+    if managed_sections.next().is_some() {
+        return Err(Error::MultipleManagedSections);
+    }
+    let high = template.pressure_high.unwrap();
+    let low = template.pressure_low;
+    let poll = Duration::from_secs(template.poll_interval_sec);
+    let pressure_path = base.join("proc/pressure/memory");
+
+    /* Indices we hot-added ourselves; everything else is left alone. */
+    let mut managed: Vec<String> = Vec::new();
+
+    loop {
+        let pressure = read_pressure(&pressure_path)?;
+        let count = managed.len() as u64;
+
+        if pressure.full_avg10 >= high && count < template.max_devices {
+            let name = hot_add(&base, template)?;
+            println!("Memory pressure {:.2}% >= {:.2}%, added {}", pressure.full_avg10, high, name);
+            managed.push(name);
+        } else if pressure.full_avg10 <= low && count > template.min_devices {
+            if let Some(pos) = managed.iter().position(|name| device_empty(&base, name)) {
+                let name = managed.remove(pos);
+                hot_remove(&base, &name)?;
+                println!("Memory pressure {:.2}% <= {:.2}%, removed {}", pressure.full_avg10, low, name);
+            }
+        }
+
+        thread::sleep(poll);
+    }
+}
+
+fn read_pressure(path: &Path) -> Result<Pressure, Error> {
+    let content = fs::read_to_string(path).with_path(path)?;
+    parse_pressure(&content).ok_or_else(|| Error::PressureParse(path.to_path_buf()))
+}
+
+/// Pull the `full` avg10 stall percentage out of a `/proc/pressure/memory` dump.
+fn parse_pressure(content: &str) -> Option<Pressure> {
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("full ") {
+            for field in rest.split_whitespace() {
+                if let Some(val) = field.strip_prefix("avg10=") {
+                    return val.parse().ok().map(|full_avg10| Pressure { full_avg10 });
+                }
+            }
+        }
+    }
+    None
+}
+
+fn hot_add(base: &Path, template: &Device) -> Result<String, Error> {
+    let name = setup::hot_add(base)?;
+    let sys_block = base.join("sys/block").join(&name);
+
+    setup::configure_device(&sys_block, template)?;
+
+    let dev_node = base.join("dev").join(&name);
+    run("mkswap", &dev_node, Error::Mkswap, |device, status| Error::MkswapStatus { device, status })?;
+    run("swapon", &dev_node, Error::Swapon, |device, status| Error::SwaponStatus { device, status })?;
+
+    Ok(name)
+}
+
+fn hot_remove(base: &Path, name: &str) -> Result<(), Error> {
+    let dev_node = base.join("dev").join(name);
+    run("swapoff", &dev_node, Error::Swapoff, |device, status| Error::SwapoffStatus { device, status })?;
+
+    let index = &name[4..];
+    let hot_remove = base.join("sys/class/zram-control/hot_remove");
+    write_attr(&hot_remove, index)
+}
+
+/// Whether the device currently stores no pages, per the first `mm_stat` field.
+fn device_empty(base: &Path, name: &str) -> bool {
+    let path = base.join("sys/block").join(name).join("mm_stat");
+    match fs::read_to_string(&path) {
+        Ok(content) => content.split_whitespace().next() == Some("0"),
+        Err(_) => false,
+    }
+}
+
+fn run(program: &str,
+       dev_node: &Path,
+       spawn_err: fn(std::io::Error) -> Error,
+       status_err: fn(PathBuf, std::process::ExitStatus) -> Error)
+       -> Result<(), Error> {
+    let status = Command::new(program).arg(dev_node).status().map_err(spawn_err)?;
+    if !status.success() {
+        return Err(status_err(dev_node.to_path_buf(), status));
+    }
+    Ok(())
+}
+
+fn write_attr(path: &Path, value: &str) -> Result<(), Error> {
+    fs::write(path, value).with_path(path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pressure;
+
+    #[test]
+    fn parses_full_avg10() {
+        let psi = "some avg10=1.23 avg60=0.50 avg300=0.10 total=123456\n\
+                   full avg10=4.56 avg60=0.25 avg300=0.05 total=65432\n";
+        let pressure = parse_pressure(psi).expect("full line present");
+        assert_eq!(pressure.full_avg10, 4.56);
+    }
+
+    #[test]
+    fn missing_full_line_is_none() {
+        let psi = "some avg10=1.23 avg60=0.50 avg300=0.10 total=123456\n";
+        assert!(parse_pressure(psi).is_none());
+    }
+}
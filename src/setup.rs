@@ -0,0 +1,100 @@
+/* SPDX-License-Identifier: MIT */
+
+use crate::config::Device;
+use crate::error::{Error, ResultExt};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+
+pub fn run_device_setup(root: Cow<'static, str>, device: &Device) -> Result<(), Error> {
+    let base = Path::new(&root[..]);
+
+    let sys_block = base.join("sys/block").join(&device.name);
+
+    /* The module usually pre-creates the configured device via modprobe's
+     * num_devices; if it didn't, allocate one ourselves. zram-control picks the
+     * index, so all we can do is accept the one it hands back when it matches
+     * the configured name — otherwise the swap unit and --setup-device %i, both
+     * keyed to that name, would point at a device we never formatted. */
+    if !sys_block.exists() {
+        let allocated = hot_add(base)?;
+        if allocated != device.name {
+            return Err(Error::AllocatedIndexMismatch { requested: device.name.clone(), allocated });
+        }
+    }
+
+    configure_device(&sys_block, device)?;
+
+    let dev_node = base.join("dev").join(&device.name);
+    let status = Command::new("mkswap").arg(&dev_node).status()
+        .map_err(Error::Mkswap)?;
+    if !status.success() {
+        return Err(Error::MkswapStatus { device: dev_node, status });
+    }
+
+    Ok(())
+}
+
+/// Write a device's compression, backing-device, size and memory-limit
+/// attributes in the order the kernel requires.
+///
+/// comp_algorithm and the stream count have to be set while the device is still
+/// empty, i.e. before disksize is written. The backing device, which
+/// idle/incompressible pages are written back to, likewise has to be attached
+/// before the device is sized.
+pub(crate) fn configure_device(sys_block: &Path, device: &Device) -> Result<(), Error> {
+    if let Some(comp_algorithm) = device.compression_algorithm.as_ref() {
+        write_attr(&sys_block.join("comp_algorithm"), comp_algorithm)?;
+    }
+
+    if let Some(writeback_device) = device.writeback_device.as_ref() {
+        write_attr(&sys_block.join("backing_dev"), &writeback_device.display().to_string())?;
+    }
+
+    write_attr(&sys_block.join("disksize"), &device.disksize.to_string())?;
+
+    if let Some(mem_limit_mb) = device.mem_limit_mb {
+        write_attr(&sys_block.join("mem_limit"), &format!("{}M", mem_limit_mb))?;
+    }
+
+    Ok(())
+}
+
+/// Allocate a fresh zram device through zram-control and return its name.
+///
+/// `hot_add` is a read-only class attribute: reading it allocates the next free
+/// index and yields it; the index can't be chosen by the caller.
+pub(crate) fn hot_add(base: &Path) -> Result<String, Error> {
+    let hot_add = base.join("sys/class/zram-control/hot_add");
+    let index = fs::read_to_string(&hot_add).with_path(&hot_add)?;
+    Ok(format!("zram{}", index.trim()))
+}
+
+pub fn run_writeback(root: Cow<'static, str>, device: &Device) -> Result<(), Error> {
+    let sys_block = Path::new(&root[..]).join("sys/block").join(&device.name);
+
+    /* Mark pages as idle: either everything, or only pages untouched for at
+     * least the configured number of seconds. */
+    let idle = match device.writeback_idle_seconds {
+        Some(seconds) => seconds.to_string(),
+        None => "all".to_string(),
+    };
+    write_attr(&sys_block.join("idle"), &idle)?;
+
+    /* Cap how many pages a single cycle is allowed to spill, if asked to. */
+    if let Some(limit) = device.writeback_limit {
+        write_attr(&sys_block.join("writeback_limit_enable"), "1")?;
+        write_attr(&sys_block.join("writeback_limit"), &limit.to_string())?;
+    }
+
+    write_attr(&sys_block.join("writeback"), "idle")?;
+
+    Ok(())
+}
+
+fn write_attr(path: &Path, value: &str) -> Result<(), Error> {
+    fs::write(path, value).with_path(path)?;
+    Ok(())
+}